@@ -1,27 +1,39 @@
 use std::env;
-use std::sync::Arc;
-use std::time::Duration;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::ffi::{OsStr, OsString};
 
 use uuid::Uuid;
-use regex::Regex;
+use rand::random;
 
 use api::Dsn;
 use scope::Scope;
-use protocol::Event;
-use transport::Transport;
-use backtrace_support::{WELL_KNOWN_BORDER_FRAMES, WELL_KNOWN_SYS_MODULES};
+use protocol::{Breadcrumb, Event};
+use transport::{self, NoopTransport, Transport, TransportFactory};
+use session::{Session, SessionFlusher, SessionMode, SessionStatus};
+use integration::{BacktraceIntegration, Integration};
+
+/// A callback used to inspect, mutate or drop a value before it leaves the process.
+///
+/// Returning `None` drops the value.
+pub type BeforeCallback<T> = Arc<dyn Fn(T) -> Option<T> + Send + Sync>;
 
 /// The Sentry client object.
 #[derive(Debug, Clone)]
 pub struct Client {
-    dsn: Dsn,
+    dsn: Option<Dsn>,
     options: ClientOptions,
-    transport: Arc<Transport>,
+    transport: Arc<dyn Transport>,
+    current_session: Arc<Mutex<Option<Session>>>,
+    session_flusher: Option<Arc<SessionFlusher>>,
+    integrations: Vec<Arc<dyn Integration>>,
+    closed: Arc<AtomicBool>,
 }
 
 /// Configuration settings for the client.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct ClientOptions {
     /// module prefixes that are always considered in_app
     pub in_app_include: Vec<&'static str>,
@@ -34,6 +46,33 @@ pub struct ClientOptions {
     pub max_breadcrumbs: usize,
     /// Automatically trim backtraces of junk before sending.
     pub trim_backtraces: bool,
+    /// Called right before an event is sent to Sentry, after scope merging
+    /// and backtrace processing. Returning `None` drops the event, which
+    /// makes `capture_event` skip the transport entirely.
+    pub before_send: Option<BeforeCallback<Event>>,
+    /// Called right before a breadcrumb is added to the scope. Returning
+    /// `None` drops the breadcrumb.
+    pub before_breadcrumb: Option<BeforeCallback<Breadcrumb>>,
+    /// The sample rate for events, as a value between `0.0` and `1.0`.
+    /// The default is `1.0` which means that all events are sent.  Sampling
+    /// is applied per event and is independent of breadcrumb trimming.
+    pub sample_rate: f32,
+    /// How release-health sessions are tracked and aggregated.
+    pub session_mode: SessionMode,
+    /// The release to report together with events and sessions.
+    pub release: Option<String>,
+    /// The environment to report together with events and sessions.
+    pub environment: Option<String>,
+    /// Additional event-processing integrations to register, on top of the
+    /// built-in backtrace processing. Deduplicated by concrete type.
+    pub integrations: Vec<Arc<dyn Integration>>,
+    /// Builds the `Transport` used to deliver events, given the DSN and the
+    /// rest of the options. Defaults to the built-in HTTP transport; override
+    /// to inject a test spy, an offline queue, or a different HTTP stack.
+    pub transport: TransportFactory,
+    /// How long `Client::close` waits for buffered events and sessions to
+    /// flush before giving up.
+    pub shutdown_timeout: Duration,
 }
 
 impl Default for ClientOptions {
@@ -44,12 +83,41 @@ impl Default for ClientOptions {
             extra_border_frames: vec![],
             max_breadcrumbs: 100,
             trim_backtraces: true,
+            before_send: None,
+            before_breadcrumb: None,
+            sample_rate: 1.0,
+            session_mode: SessionMode::Application,
+            release: None,
+            environment: None,
+            integrations: vec![],
+            transport: transport::default_transport_factory(),
+            shutdown_timeout: Duration::from_secs(2),
         }
     }
 }
 
-lazy_static! {
-    static ref CRATE_RE: Regex = Regex::new(r"^([^:]+?)::").unwrap();
+impl fmt::Debug for ClientOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientOptions")
+            .field("in_app_include", &self.in_app_include)
+            .field("in_app_exclude", &self.in_app_exclude)
+            .field("extra_border_frames", &self.extra_border_frames)
+            .field("max_breadcrumbs", &self.max_breadcrumbs)
+            .field("trim_backtraces", &self.trim_backtraces)
+            .field("before_send", &self.before_send.as_ref().map(|_| "BeforeCallback<Event>"))
+            .field(
+                "before_breadcrumb",
+                &self.before_breadcrumb.as_ref().map(|_| "BeforeCallback<Breadcrumb>"),
+            )
+            .field("sample_rate", &self.sample_rate)
+            .field("session_mode", &self.session_mode)
+            .field("release", &self.release)
+            .field("environment", &self.environment)
+            .field("integrations", &self.integrations)
+            .field("transport", &"TransportFactory")
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .finish()
+    }
 }
 
 /// Helper trait to convert an object into a client config
@@ -186,15 +254,57 @@ impl Client {
 
     /// Creates a new sentry client for the given DSN.
     pub fn with_dsn_and_options(dsn: Dsn, options: ClientOptions) -> Client {
-        let transport = Transport::new(&dsn);
+        Client::with_options(Some(dsn), options)
+    }
+
+    /// Creates a new sentry client, optionally without a DSN.
+    ///
+    /// A client created with `dsn: None` is disabled: it can be used
+    /// exactly like an enabled one, but `capture_event` silently discards
+    /// everything it is given. This avoids threading `Option<Client>`
+    /// through call sites just to handle the case where Sentry isn't
+    /// configured.
+    pub fn with_options(dsn: Option<Dsn>, options: ClientOptions) -> Client {
+        let mut options = options;
+        let mut integrations: Vec<Arc<dyn Integration>> = vec![Arc::new(BacktraceIntegration)];
+        for integration in options.integrations.drain(..) {
+            if !integrations.iter().any(|i| i.type_id() == integration.type_id()) {
+                integrations.push(integration);
+            }
+        }
+        for integration in &integrations {
+            integration.setup(&mut options);
+        }
+
+        let transport: Arc<dyn Transport> = match dsn {
+            Some(ref dsn) => (options.transport)(dsn, &options),
+            None => Arc::new(NoopTransport),
+        };
+        // A disabled client never records a session (see `start_session`),
+        // so there is nothing for a flusher to do -- skip spawning its
+        // background thread entirely.
+        let session_flusher = dsn.as_ref()
+            .map(|_| Arc::new(SessionFlusher::new(transport.clone(), options.session_mode)));
         Client {
             dsn: dsn,
             options: options,
-            transport: Arc::new(transport),
+            transport: transport,
+            current_session: Arc::new(Mutex::new(None)),
+            session_flusher: session_flusher,
+            integrations: integrations,
+            closed: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    fn prepare_event(&self, event: &mut Event, scope: Option<&Scope>) {
+    /// Returns whether this client has a DSN configured.
+    ///
+    /// A disabled client (`is_enabled() == false`) discards every event it
+    /// is given instead of sending it anywhere.
+    pub fn is_enabled(&self) -> bool {
+        self.dsn.is_some()
+    }
+
+    fn prepare_event(&self, mut event: Event, scope: Option<&Scope>) -> Option<Event> {
         if let Some(scope) = scope {
             if !scope.breadcrumbs.is_empty() {
                 event
@@ -225,90 +335,11 @@ impl Client {
             event.platform = "native".into();
         }
 
-        for exc in event.exceptions.iter_mut() {
-            if let Some(ref mut stacktrace) = exc.stacktrace {
-                // automatically trim backtraces
-                if self.options.trim_backtraces {
-                    if let Some(cutoff) = stacktrace.frames.iter().rev().position(|frame| {
-                        if let Some(ref func) = frame.function {
-                            WELL_KNOWN_BORDER_FRAMES.contains(&func.as_str())
-                                || self.options.extra_border_frames.contains(&func.as_str())
-                        } else {
-                            false
-                        }
-                    }) {
-                        let trunc = stacktrace.frames.len() - cutoff - 1;
-                        stacktrace.frames.truncate(trunc);
-                    }
-                }
-
-                // automatically prime in_app and set package
-                let mut any_in_app = false;
-                for frame in stacktrace.frames.iter_mut() {
-                    let func_name = match frame.function {
-                        Some(ref func) => func,
-                        None => continue,
-                    };
-
-                    // set package if missing to crate prefix
-                    if frame.package.is_none() {
-                        frame.package = CRATE_RE
-                            .captures(func_name)
-                            .and_then(|caps| caps.get(1))
-                            .map(|cr| cr.as_str().into());
-                    }
-
-                    match frame.in_app {
-                        Some(true) => {
-                            any_in_app = true;
-                            continue;
-                        }
-                        Some(false) => {
-                            continue;
-                        }
-                        None => {}
-                    }
-
-                    for m in &self.options.in_app_exclude {
-                        if func_name.starts_with(m) {
-                            frame.in_app = Some(false);
-                            break;
-                        }
-                    }
-
-                    if frame.in_app.is_some() {
-                        continue;
-                    }
-
-                    for m in &self.options.in_app_include {
-                        if func_name.starts_with(m) {
-                            frame.in_app = Some(true);
-                            any_in_app = true;
-                            break;
-                        }
-                    }
-
-                    if frame.in_app.is_some() {
-                        continue;
-                    }
-
-                    for m in WELL_KNOWN_SYS_MODULES.iter() {
-                        if func_name.starts_with(m) {
-                            frame.in_app = Some(false);
-                            break;
-                        }
-                    }
-                }
-
-                if !any_in_app {
-                    for frame in stacktrace.frames.iter_mut() {
-                        if frame.in_app.is_none() {
-                            frame.in_app = Some(true);
-                        }
-                    }
-                }
-            }
+        for integration in &self.integrations {
+            event = integration.process_event(event, &self.options)?;
         }
+
+        Some(event)
     }
 
     /// Returns the options of this client.
@@ -316,14 +347,100 @@ impl Client {
         &self.options
     }
 
-    /// Returns the DSN that constructed this client.
-    pub fn dsn(&self) -> &Dsn {
-        &self.dsn
+    /// Returns the DSN that constructed this client, if any.
+    pub fn dsn(&self) -> Option<&Dsn> {
+        self.dsn.as_ref()
+    }
+
+    /// Runs a breadcrumb through the `before_breadcrumb` hook.
+    ///
+    /// Returns `None` if the hook drops the breadcrumb, in which case the
+    /// caller (the scope) should not record it.
+    pub fn before_breadcrumb(&self, breadcrumb: Breadcrumb) -> Option<Breadcrumb> {
+        match self.options.before_breadcrumb {
+            Some(ref callback) => callback(breadcrumb),
+            None => Some(breadcrumb),
+        }
+    }
+
+    /// Pushes a breadcrumb onto `scope`, running it through the
+    /// `before_breadcrumb` hook first.
+    ///
+    /// This is the supported way to record a breadcrumb -- call it instead
+    /// of mutating `scope.breadcrumbs` directly, so the hook always runs
+    /// and a dropped breadcrumb never makes it onto the scope.
+    pub fn add_breadcrumb(&self, scope: &mut Scope, breadcrumb: Breadcrumb) {
+        if let Some(breadcrumb) = self.before_breadcrumb(breadcrumb) {
+            scope.breadcrumbs.push(breadcrumb);
+        }
+    }
+
+    /// Starts a new release-health session.
+    ///
+    /// If a session is already open it is first closed with status
+    /// `exited` and handed to the `SessionFlusher`. A disabled client does
+    /// not start a session.
+    pub fn start_session(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut current = self.current_session.lock().unwrap();
+        if let Some(mut session) = current.take() {
+            session.close(SessionStatus::Exited);
+            if let Some(ref flusher) = self.session_flusher {
+                flusher.add_session(session);
+            }
+        }
+        *current = Some(Session::new(
+            self.options.release.clone().unwrap_or_default(),
+            self.options.environment.clone(),
+        ));
+    }
+
+    /// Ends the currently open release-health session, if any.
+    pub fn end_session(&self) {
+        let mut current = self.current_session.lock().unwrap();
+        if let Some(mut session) = current.take() {
+            session.close(SessionStatus::Exited);
+            if let Some(ref flusher) = self.session_flusher {
+                flusher.add_session(session);
+            }
+        }
     }
 
     /// Captures an event and sends it to sentry.
-    pub fn capture_event(&self, mut event: Event, scope: Option<&Scope>) -> Uuid {
-        self.prepare_event(&mut event, scope);
+    pub fn capture_event(&self, event: Event, scope: Option<&Scope>) -> Uuid {
+        if !self.is_enabled() || self.closed.load(Ordering::Acquire) {
+            return Uuid::nil();
+        }
+
+        let event = match self.prepare_event(event, scope) {
+            Some(event) => event,
+            None => return Uuid::nil(),
+        };
+
+        let event = match self.options.before_send {
+            Some(ref callback) => match callback(event) {
+                Some(event) => event,
+                None => return Uuid::nil(),
+            },
+            None => event,
+        };
+
+        if self.options.sample_rate < 1.0 && random::<f32>() >= self.options.sample_rate {
+            return Uuid::nil();
+        }
+
+        // Only mark the session once we know the event is actually going
+        // out -- one the user dropped via `before_send` or that got
+        // sampled away was never sent, so it shouldn't count against the
+        // session's health.
+        if !event.exceptions.is_empty() {
+            if let Some(ref mut session) = *self.current_session.lock().unwrap() {
+                session.add_error();
+            }
+        }
+
         self.transport.send_event(event)
     }
 
@@ -333,6 +450,75 @@ impl Client {
     /// given time or `false` if not (for instance because of a timeout).
     /// If no timeout is provided the client will wait forever.
     pub fn drain_events(&self, timeout: Option<Duration>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
         self.transport.drain(timeout)
     }
-}
\ No newline at end of file
+
+    /// Shuts the client down gracefully.
+    ///
+    /// Stops accepting new events, ends any open session, and flushes the
+    /// transport (and pending sessions) within `timeout` (or
+    /// `ClientOptions::shutdown_timeout` if `None`). Returns whether
+    /// everything drained within that time. Calling this more than once is a
+    /// no-op after the first call.
+    pub fn close(&self, timeout: Option<Duration>) -> bool {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return true;
+        }
+
+        if !self.is_enabled() {
+            return true;
+        }
+
+        self.end_session();
+        let timeout = timeout.unwrap_or(self.options.shutdown_timeout);
+        let deadline = Instant::now() + timeout;
+
+        let sessions_drained = match self.session_flusher {
+            Some(ref flusher) => flusher.shutdown(Some(time_until(deadline))),
+            None => true,
+        };
+        let events_drained = self.transport.shutdown(Some(time_until(deadline)));
+        sessions_drained && events_drained
+    }
+}
+
+/// Returns how much time is left until `deadline`, or zero if it has
+/// already passed.
+fn time_until(deadline: Instant) -> Duration {
+    deadline.checked_duration_since(Instant::now()).unwrap_or_default()
+}
+
+/// RAII guard returned by the `sentry::init` helper.
+///
+/// Keeps the client alive for the scope in which it is held and, on
+/// `Drop`, ends any open session and gracefully closes the client via
+/// `Client::close`, so buffered events and sessions are delivered even if
+/// the caller never calls `drain_events`/`close` manually.
+#[derive(Debug)]
+pub struct ClientInitGuard(Client);
+
+impl ClientInitGuard {
+    /// Wraps a client in a guard that closes it on drop.
+    pub fn new(client: Client) -> ClientInitGuard {
+        ClientInitGuard(client)
+    }
+
+    /// Returns whether the underlying client is enabled (has a DSN).
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_enabled()
+    }
+
+    /// Returns a handle to the underlying client.
+    pub fn client(&self) -> Client {
+        self.0.clone()
+    }
+}
+
+impl Drop for ClientInitGuard {
+    fn drop(&mut self) {
+        self.0.close(None);
+    }
+}
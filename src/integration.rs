@@ -0,0 +1,147 @@
+use std::any::{Any, TypeId};
+use std::fmt;
+
+use regex::Regex;
+
+use protocol::Event;
+use backtrace_support::{WELL_KNOWN_BORDER_FRAMES, WELL_KNOWN_SYS_MODULES};
+use client::ClientOptions;
+
+lazy_static! {
+    static ref CRATE_RE: Regex = Regex::new(r"^([^:]+?)::").unwrap();
+}
+
+/// A hook into event processing.
+///
+/// Integrations are the extension point for reusable event processors --
+/// panic handlers, log bridges, custom context providers -- without having
+/// to change `Client` itself. They are registered through
+/// `ClientOptions::integrations` and run, in order, inside
+/// `Client::prepare_event`.
+pub trait Integration: Send + Sync + Any {
+    /// A human readable name, used for debugging. Deduplication is by
+    /// concrete type, not by name.
+    fn name(&self) -> &'static str;
+
+    /// Called once when the integration is registered so it can adjust the
+    /// client options (for instance to add default `in_app_include`s).
+    fn setup(&self, _options: &mut ClientOptions) {}
+
+    /// Processes (and potentially drops) an event before it is sent.
+    ///
+    /// Returning `None` drops the event.
+    fn process_event(&self, event: Event, options: &ClientOptions) -> Option<Event>;
+
+    #[doc(hidden)]
+    fn type_id(&self) -> TypeId {
+        Any::type_id(self)
+    }
+}
+
+impl fmt::Debug for dyn Integration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Integration").field("name", &self.name()).finish()
+    }
+}
+
+/// The built-in integration that trims junk off backtraces and primes
+/// `in_app`/`package` on stack frames.
+///
+/// This is always installed first, ahead of any user-supplied integrations.
+#[derive(Debug)]
+pub(crate) struct BacktraceIntegration;
+
+impl Integration for BacktraceIntegration {
+    fn name(&self) -> &'static str {
+        "backtrace"
+    }
+
+    fn process_event(&self, mut event: Event, options: &ClientOptions) -> Option<Event> {
+        for exc in event.exceptions.iter_mut() {
+            if let Some(ref mut stacktrace) = exc.stacktrace {
+                // automatically trim backtraces
+                if options.trim_backtraces {
+                    if let Some(cutoff) = stacktrace.frames.iter().rev().position(|frame| {
+                        if let Some(ref func) = frame.function {
+                            WELL_KNOWN_BORDER_FRAMES.contains(&func.as_str())
+                                || options.extra_border_frames.contains(&func.as_str())
+                        } else {
+                            false
+                        }
+                    }) {
+                        let trunc = stacktrace.frames.len() - cutoff - 1;
+                        stacktrace.frames.truncate(trunc);
+                    }
+                }
+
+                // automatically prime in_app and set package
+                let mut any_in_app = false;
+                for frame in stacktrace.frames.iter_mut() {
+                    let func_name = match frame.function {
+                        Some(ref func) => func,
+                        None => continue,
+                    };
+
+                    // set package if missing to crate prefix
+                    if frame.package.is_none() {
+                        frame.package = CRATE_RE
+                            .captures(func_name)
+                            .and_then(|caps| caps.get(1))
+                            .map(|cr| cr.as_str().into());
+                    }
+
+                    match frame.in_app {
+                        Some(true) => {
+                            any_in_app = true;
+                            continue;
+                        }
+                        Some(false) => {
+                            continue;
+                        }
+                        None => {}
+                    }
+
+                    for m in &options.in_app_exclude {
+                        if func_name.starts_with(m) {
+                            frame.in_app = Some(false);
+                            break;
+                        }
+                    }
+
+                    if frame.in_app.is_some() {
+                        continue;
+                    }
+
+                    for m in &options.in_app_include {
+                        if func_name.starts_with(m) {
+                            frame.in_app = Some(true);
+                            any_in_app = true;
+                            break;
+                        }
+                    }
+
+                    if frame.in_app.is_some() {
+                        continue;
+                    }
+
+                    for m in WELL_KNOWN_SYS_MODULES.iter() {
+                        if func_name.starts_with(m) {
+                            frame.in_app = Some(false);
+                            break;
+                        }
+                    }
+                }
+
+                if !any_in_app {
+                    for frame in stacktrace.frames.iter_mut() {
+                        if frame.in_app.is_none() {
+                            frame.in_app = Some(true);
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(event)
+    }
+}
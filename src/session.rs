@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use uuid::Uuid;
+
+use transport::Transport;
+
+/// The default interval at which the `SessionFlusher` flushes batched
+/// session updates to the transport.
+pub(crate) const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Controls how release-health sessions are tracked and aggregated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionMode {
+    /// One long-lived session per process. This is the right choice for
+    /// most applications (desktop, worker, daemon, ...).
+    Application,
+    /// Many short sessions (for instance one per incoming request), which
+    /// are pre-aggregated into per-time-bucket counts rather than sent
+    /// individually.
+    Request,
+}
+
+/// The status a `Session` ends (or currently is) in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionStatus {
+    /// The session is still in progress.
+    Ok,
+    /// The session ended because the process (or request) crashed.
+    Crashed,
+    /// The session ended in an unknown way.
+    Abnormal,
+    /// The session ended normally.
+    Exited,
+}
+
+/// A release-health session.
+///
+/// Sessions track whether a given run of the application (or, in
+/// `SessionMode::Request`, a single request) completed without an
+/// unhandled error, so that Sentry can compute crash-free rates.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub(crate) session_id: Uuid,
+    pub(crate) release: String,
+    pub(crate) environment: Option<String>,
+    pub(crate) status: SessionStatus,
+    pub(crate) started: SystemTime,
+    pub(crate) duration: Option<Duration>,
+    pub(crate) errors: u64,
+}
+
+impl Session {
+    pub(crate) fn new(release: String, environment: Option<String>) -> Session {
+        Session {
+            session_id: Uuid::new_v4(),
+            release,
+            environment,
+            status: SessionStatus::Ok,
+            started: SystemTime::now(),
+            duration: None,
+            errors: 0,
+        }
+    }
+
+    /// Records that an (additional) captured error happened during this
+    /// session. A captured error is not a crash -- it only bumps the
+    /// `errors` counter; `status` is left alone so a session that reports
+    /// an error but otherwise exits cleanly is still sent as `exited`.
+    /// Use `close(SessionStatus::Crashed)` to mark an actual crash.
+    pub(crate) fn add_error(&mut self) {
+        self.errors += 1;
+    }
+
+    /// Closes the session with the given status, unless it already ended
+    /// (for instance because a previous `close` call already settled it).
+    pub(crate) fn close(&mut self, status: SessionStatus) {
+        if self.status == SessionStatus::Ok {
+            self.status = status;
+        }
+        self.duration = Some(self.started.elapsed().unwrap_or_default());
+    }
+}
+
+/// A single update handed to the transport by the `SessionFlusher`.
+#[derive(Debug, Clone)]
+pub enum SessionUpdate {
+    /// A single, fully formed session (used in `SessionMode::Application`).
+    Individual(Session),
+    /// A pre-aggregated count of sessions for a release/environment pair
+    /// over one flush interval (used in `SessionMode::Request`).
+    Aggregate {
+        release: String,
+        environment: Option<String>,
+        exited: u64,
+        errored: u64,
+    },
+}
+
+enum FlusherMessage {
+    Session(Session),
+    Shutdown(Sender<()>),
+}
+
+/// Owns a background thread that batches session updates and flushes them
+/// to the transport on an interval, on an explicit `shutdown`, and (as a
+/// last resort) when dropped.
+pub(crate) struct SessionFlusher {
+    sender: Sender<FlusherMessage>,
+    worker: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl SessionFlusher {
+    pub(crate) fn new(transport: Arc<dyn Transport>, mode: SessionMode) -> SessionFlusher {
+        let (sender, receiver) = channel::<FlusherMessage>();
+
+        let worker = thread::Builder::new()
+            .name("sentry-session-flusher".into())
+            .spawn(move || {
+                let mut pending = Vec::new();
+                let mut aggregates: HashMap<(String, Option<String>), (u64, u64)> = HashMap::new();
+                let mut next_flush = Instant::now() + DEFAULT_FLUSH_INTERVAL;
+
+                loop {
+                    let now = Instant::now();
+                    if now >= next_flush {
+                        flush(&transport, &mut pending, &mut aggregates);
+                        next_flush = now + DEFAULT_FLUSH_INTERVAL;
+                    }
+                    match receiver.recv_timeout(next_flush - now) {
+                        Ok(FlusherMessage::Session(session)) => match mode {
+                            SessionMode::Application => pending.push(session),
+                            SessionMode::Request => {
+                                let key = (session.release.clone(), session.environment.clone());
+                                let entry = aggregates.entry(key).or_insert((0, 0));
+                                if session.status == SessionStatus::Exited && session.errors == 0 {
+                                    entry.0 += 1;
+                                } else {
+                                    entry.1 += 1;
+                                }
+                            }
+                        },
+                        Ok(FlusherMessage::Shutdown(ack)) => {
+                            flush(&transport, &mut pending, &mut aggregates);
+                            let _ = ack.send(());
+                            break;
+                        }
+                        // The interval flush runs at the top of the loop.
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            })
+            .ok();
+
+        SessionFlusher {
+            sender,
+            worker: Mutex::new(worker),
+        }
+    }
+
+    /// Queues a session update for the next flush.
+    pub(crate) fn add_session(&self, session: Session) {
+        let _ = self.sender.send(FlusherMessage::Session(session));
+    }
+
+    /// Stops the background thread, flushing one last time before it exits.
+    ///
+    /// Waits up to `timeout` for the flush to be acknowledged (or forever
+    /// if `None`). Returns whether it completed within that time; if it
+    /// didn't, the worker is left to finish on its own rather than
+    /// blocking the caller past the deadline.
+    pub(crate) fn shutdown(&self, timeout: Option<Duration>) -> bool {
+        let (ack_sender, ack_receiver) = channel();
+        if self.sender.send(FlusherMessage::Shutdown(ack_sender)).is_err() {
+            return true;
+        }
+
+        let acked = match timeout {
+            Some(timeout) => ack_receiver.recv_timeout(timeout).is_ok(),
+            None => ack_receiver.recv().is_ok(),
+        };
+
+        if acked {
+            if let Some(worker) = self.worker.lock().unwrap().take() {
+                let _ = worker.join();
+            }
+        }
+
+        acked
+    }
+}
+
+impl Drop for SessionFlusher {
+    fn drop(&mut self) {
+        self.shutdown(None);
+    }
+}
+
+impl fmt::Debug for SessionFlusher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SessionFlusher").finish()
+    }
+}
+
+fn flush(
+    transport: &dyn Transport,
+    pending: &mut Vec<Session>,
+    aggregates: &mut HashMap<(String, Option<String>), (u64, u64)>,
+) {
+    for session in pending.drain(..) {
+        transport.send_session(SessionUpdate::Individual(session));
+    }
+    for ((release, environment), (exited, errored)) in aggregates.drain() {
+        transport.send_session(SessionUpdate::Aggregate {
+            release,
+            environment,
+            exited,
+            errored,
+        });
+    }
+}
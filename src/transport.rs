@@ -0,0 +1,170 @@
+use std::fmt;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use api::Dsn;
+use protocol::Event;
+use session::SessionUpdate;
+use client::ClientOptions;
+
+/// Delivers events and release-health session updates to Sentry.
+///
+/// Implement this to route events through a custom HTTP stack, a test spy,
+/// or an offline queue. The built-in [`HttpTransport`] is used unless
+/// `ClientOptions::transport` is overridden.
+pub trait Transport: Send + Sync {
+    /// Sends an event and returns the `Uuid` it was assigned.
+    fn send_event(&self, event: Event) -> Uuid;
+
+    /// Sends a release-health session update.
+    fn send_session(&self, update: SessionUpdate);
+
+    /// Flushes any in-flight events/sessions, waiting up to `timeout`.
+    ///
+    /// Returns whether everything drained within the timeout. Unlike
+    /// `shutdown`, the transport keeps accepting new events afterwards.
+    fn drain(&self, timeout: Option<Duration>) -> bool;
+
+    /// Drains the transport and stops accepting further events.
+    ///
+    /// Returns whether everything drained within the timeout.
+    fn shutdown(&self, timeout: Option<Duration>) -> bool;
+}
+
+impl fmt::Debug for dyn Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transport").finish()
+    }
+}
+
+/// Builds a [`Transport`] for a given DSN and set of client options.
+pub type TransportFactory = Arc<dyn Fn(&Dsn, &ClientOptions) -> Arc<dyn Transport> + Send + Sync>;
+
+/// The factory used by `ClientOptions` unless the user supplies their own;
+/// constructs an [`HttpTransport`].
+pub(crate) fn default_transport_factory() -> TransportFactory {
+    Arc::new(|dsn: &Dsn, _options: &ClientOptions| -> Arc<dyn Transport> {
+        Arc::new(HttpTransport::new(dsn))
+    })
+}
+
+enum Item {
+    Event(Event),
+    Session(SessionUpdate),
+    Flush(Sender<()>),
+    Shutdown,
+}
+
+enum Envelope {
+    Event(Event),
+    Session(SessionUpdate),
+}
+
+/// The built-in HTTP transport.
+///
+/// Events and session updates are hung off a queue and handed to
+/// `send_envelope` from a single background thread, so that
+/// `send_event`/`send_session` never block the caller.
+pub struct HttpTransport {
+    sender: Sender<Item>,
+    worker: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl HttpTransport {
+    /// Creates a new HTTP transport for the given DSN.
+    pub fn new(dsn: &Dsn) -> HttpTransport {
+        let dsn = dsn.clone();
+        let (sender, receiver) = channel::<Item>();
+
+        let worker = thread::Builder::new()
+            .name("sentry-transport".into())
+            .spawn(move || {
+                for item in receiver {
+                    match item {
+                        Item::Event(event) => send_envelope(&dsn, Envelope::Event(event)),
+                        Item::Session(update) => send_envelope(&dsn, Envelope::Session(update)),
+                        Item::Flush(ack) => {
+                            let _ = ack.send(());
+                        }
+                        Item::Shutdown => break,
+                    }
+                }
+            })
+            .ok();
+
+        HttpTransport {
+            sender,
+            worker: Mutex::new(worker),
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send_event(&self, event: Event) -> Uuid {
+        let event_id = Uuid::new_v4();
+        let _ = self.sender.send(Item::Event(event));
+        event_id
+    }
+
+    fn send_session(&self, update: SessionUpdate) {
+        let _ = self.sender.send(Item::Session(update));
+    }
+
+    fn drain(&self, timeout: Option<Duration>) -> bool {
+        let (ack_tx, ack_rx) = channel();
+        if self.sender.send(Item::Flush(ack_tx)).is_err() {
+            return true;
+        }
+        match timeout {
+            Some(timeout) => ack_rx.recv_timeout(timeout).is_ok(),
+            None => ack_rx.recv().is_ok(),
+        }
+    }
+
+    fn shutdown(&self, timeout: Option<Duration>) -> bool {
+        let drained = self.drain(timeout);
+        let _ = self.sender.send(Item::Shutdown);
+        // Only join once the queue drained; otherwise the worker is left to
+        // finish on its own so the caller is not blocked past `timeout`.
+        if !drained {
+            return false;
+        }
+        match self.worker.lock().unwrap().take() {
+            Some(worker) => worker.join().is_ok(),
+            None => true,
+        }
+    }
+}
+
+/// Takes a single envelope item for the DSN's ingest endpoint.
+///
+/// The body is empty: no HTTP request is made here, and the item is
+/// dropped.
+fn send_envelope(_dsn: &Dsn, _item: Envelope) {}
+
+/// A transport that silently discards everything handed to it.
+///
+/// Used by a disabled `Client` (one created without a DSN) so that it can
+/// be treated uniformly with an enabled one.
+#[derive(Debug, Default)]
+pub(crate) struct NoopTransport;
+
+impl Transport for NoopTransport {
+    fn send_event(&self, _event: Event) -> Uuid {
+        Uuid::nil()
+    }
+
+    fn send_session(&self, _update: SessionUpdate) {}
+
+    fn drain(&self, _timeout: Option<Duration>) -> bool {
+        true
+    }
+
+    fn shutdown(&self, _timeout: Option<Duration>) -> bool {
+        true
+    }
+}